@@ -0,0 +1,261 @@
+//! In-process builtin commands.
+//!
+//! A handful of commands can't be meaningfully handed to [`std::process::Command`] - a forked
+//! `cd` would change the child's working directory and nothing else, `jobs`/`wait` need direct
+//! access to the shell's [`JobTable`], and `exit` has to terminate the shell itself rather than a
+//! subprocess - so they're recognized and run in-process by [`run`] instead when one makes up a
+//! whole pipeline on its own; inside a multi-command pipeline or a `$(...)` substitution they're
+//! spawned externally like any other command. See [`crate::expand::ExpandedBatch::run`] for
+//! where this is wired into the execution path.
+use crate::expand::ExpandedCommand;
+use crate::job::{JobStatus, JobTable};
+use std::io::Write;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Names recognized as builtins by [`run`]. Exposed separately so
+/// [`crate::expand::ExpandedBatch::run`] can tell, before dispatching, whether it needs to open
+/// `stdout` up front for a builtin to write into, without running the command twice.
+const BUILTINS: &[&str] = &["cd", "pwd", "export", "exit", "jobs", "wait"];
+
+/// Whether `cmd` names a builtin recognized by [`run`].
+pub fn is_builtin(cmd: &str) -> bool {
+    BUILTINS.contains(&cmd)
+}
+
+/// Attempts to run `command` as a builtin. Returns `None` if `command` doesn't name one, so the
+/// caller falls back to spawning it externally. Any output the builtin produces is written to
+/// `stdout`, which the caller wires to a terminal, pipe, or redirect target the same way it does
+/// for an external command's `stdout` - see [`crate::expand::ExpandedBatch::run`].
+pub fn run(command: &ExpandedCommand, jobs: &mut JobTable, stdout: &mut dyn Write) -> Option<ExitStatus> {
+    match command.cmd.as_str() {
+        "cd" => Some(cd_builtin(command)),
+        "pwd" => Some(pwd_builtin(stdout)),
+        "export" => Some(export_builtin(command)),
+        "exit" => exit_builtin(command),
+        "jobs" => Some(jobs_builtin(jobs, stdout)),
+        "wait" => Some(wait_builtin(command, jobs)),
+        _ => None,
+    }
+}
+
+/// Builds an [`ExitStatus`] reporting `code` the way a real shell would, i.e. as if a child had
+/// called `exit(code)`.
+fn exit_status(code: i32) -> ExitStatus {
+    ExitStatus::from_raw(code << 8)
+}
+
+/// Changes the process's working directory. With no argument, goes to `$HOME`; `cd -` goes to
+/// the previous directory, tracked the same way bash does: in `$OLDPWD`, updated on every
+/// successful `cd`.
+fn cd_builtin(command: &ExpandedCommand) -> ExitStatus {
+    let target = match command.args.first().map(String::as_str) {
+        Some("-") => match std::env::var("OLDPWD") {
+            Ok(dir) => dir,
+            Err(_) => {
+                eprintln!("rash: cd: OLDPWD not set");
+                return exit_status(1);
+            }
+        },
+        Some(dir) => dir.to_string(),
+        None => match std::env::var("HOME") {
+            Ok(dir) => dir,
+            Err(_) => {
+                eprintln!("rash: cd: HOME not set");
+                return exit_status(1);
+            }
+        },
+    };
+
+    let previous = std::env::current_dir().ok();
+    match std::env::set_current_dir(&target) {
+        Ok(()) => {
+            if let Some(previous) = previous {
+                std::env::set_var("OLDPWD", previous);
+            }
+            exit_status(0)
+        }
+        Err(err) => {
+            eprintln!("rash: cd: {target}: {err}");
+            exit_status(1)
+        }
+    }
+}
+
+/// Writes the process's current working directory to `stdout`.
+fn pwd_builtin(stdout: &mut dyn Write) -> ExitStatus {
+    match std::env::current_dir() {
+        Ok(dir) => {
+            let _ = writeln!(stdout, "{}", dir.display());
+            exit_status(0)
+        }
+        Err(err) => {
+            eprintln!("rash: pwd: {err}");
+            exit_status(1)
+        }
+    }
+}
+
+/// Sets an environment variable from a `NAME=value` argument so it's visible to later expansions
+/// (see [`crate::expand`]) and inherited by spawned children. An argument with no `=` is treated
+/// as a no-op, same as bash's "declare without assignment" form.
+fn export_builtin(command: &ExpandedCommand) -> ExitStatus {
+    for arg in &command.args {
+        if let Some((name, value)) = arg.split_once('=') {
+            std::env::set_var(name, value);
+        }
+    }
+    exit_status(0)
+}
+
+/// Terminates the shell with the given exit code, defaulting to 0. A non-numeric argument is
+/// treated as 0, same as the missing-argument case.
+fn exit_builtin(command: &ExpandedCommand) -> Option<ExitStatus> {
+    let code = command.args.first().and_then(|arg| arg.parse().ok()).unwrap_or(0);
+    std::process::exit(code);
+}
+
+/// Writes every tracked background job's id and status to `stdout`.
+fn jobs_builtin(jobs: &mut JobTable, stdout: &mut dyn Write) -> ExitStatus {
+    for (id, command, status) in jobs.list() {
+        let status = match status {
+            JobStatus::Running => "Running",
+            JobStatus::Done => "Done",
+        };
+        let _ = writeln!(stdout, "[{id}]  {status}  {command}");
+    }
+    ExitStatus::from_raw(0)
+}
+
+/// Blocks on the job named by `wait`'s first argument, or on every tracked job if none was
+/// given. A non-numeric or missing argument is treated the same as no argument.
+fn wait_builtin(command: &ExpandedCommand, jobs: &mut JobTable) -> ExitStatus {
+    let id = command.args.first().and_then(|arg| arg.parse().ok());
+    jobs.wait(id);
+    ExitStatus::from_raw(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn command(cmd: &str, args: &[&str]) -> ExpandedCommand {
+        ExpandedCommand {
+            cmd: cmd.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+
+    /// `cd` mutates the process-wide working directory, which `cargo test`'s default
+    /// multithreaded runner shares across every test in the binary. Tests that call `cd` lock
+    /// this for their duration so they can't interleave with each other and race on which
+    /// directory is "original". Tests that only read the current directory (the `pwd` tests)
+    /// don't take it.
+    static CWD_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    fn lock_cwd() -> std::sync::MutexGuard<'static, ()> {
+        CWD_LOCK.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    #[test]
+    fn unknown_commands_are_not_builtins() {
+        let mut jobs = JobTable::new();
+        assert_eq!(run(&command("echo", &["hi"]), &mut jobs, &mut Vec::new()), None);
+    }
+
+    #[test]
+    fn jobs_builtin_succeeds_with_no_background_jobs() {
+        let mut jobs = JobTable::new();
+        let status = run(&command("jobs", &[]), &mut jobs, &mut Vec::new()).expect("jobs is a builtin");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn wait_builtin_succeeds_with_no_background_jobs() {
+        let mut jobs = JobTable::new();
+        let status = run(&command("wait", &[]), &mut jobs, &mut Vec::new()).expect("wait is a builtin");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn wait_builtin_blocks_on_the_named_job() {
+        let mut jobs = JobTable::new();
+        let child = std::process::Command::new("true").spawn().unwrap();
+        let id = jobs.spawn("true".to_string(), vec![child]);
+
+        let status = run(&command("wait", &[&id.to_string()]), &mut jobs, &mut Vec::new()).expect("wait is a builtin");
+        assert!(status.success());
+        assert_eq!(jobs.list().len(), 0);
+    }
+
+    #[test]
+    fn cd_changes_directory_and_records_oldpwd() {
+        let _guard = lock_cwd();
+        let mut jobs = JobTable::new();
+        let original = std::env::current_dir().unwrap();
+        let target = std::env::temp_dir();
+
+        let status = run(&command("cd", &[target.to_str().unwrap()]), &mut jobs, &mut Vec::new()).expect("cd is a builtin");
+        assert!(status.success());
+        assert_eq!(std::env::current_dir().unwrap(), target.canonicalize().unwrap());
+        assert_eq!(PathBuf::from(std::env::var("OLDPWD").unwrap()), original);
+
+        std::env::set_current_dir(original).unwrap();
+    }
+
+    #[test]
+    fn cd_dash_returns_to_the_previous_directory() {
+        let _guard = lock_cwd();
+        let mut jobs = JobTable::new();
+        let original = std::env::current_dir().unwrap();
+        let target = std::env::temp_dir();
+
+        run(&command("cd", &[target.to_str().unwrap()]), &mut jobs, &mut Vec::new());
+        let status = run(&command("cd", &["-"]), &mut jobs, &mut Vec::new()).expect("cd is a builtin");
+
+        assert!(status.success());
+        assert_eq!(std::env::current_dir().unwrap(), original);
+    }
+
+    #[test]
+    fn cd_to_a_missing_directory_fails() {
+        let mut jobs = JobTable::new();
+        let status = run(&command("cd", &["/no/such/rash-test-directory"]), &mut jobs, &mut Vec::new()).expect("cd is a builtin");
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn export_sets_an_environment_variable() {
+        let mut jobs = JobTable::new();
+        let status = run(&command("export", &["RASH_TEST_VAR=hello"]), &mut jobs, &mut Vec::new()).expect("export is a builtin");
+        assert!(status.success());
+        assert_eq!(std::env::var("RASH_TEST_VAR").unwrap(), "hello");
+    }
+
+    #[test]
+    fn pwd_reports_success() {
+        let mut jobs = JobTable::new();
+        let status = run(&command("pwd", &[]), &mut jobs, &mut Vec::new()).expect("pwd is a builtin");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn pwd_writes_the_current_directory_to_stdout() {
+        let mut jobs = JobTable::new();
+        let mut stdout = Vec::new();
+        run(&command("pwd", &[]), &mut jobs, &mut stdout).expect("pwd is a builtin");
+        assert_eq!(
+            String::from_utf8(stdout).unwrap().trim_end(),
+            std::env::current_dir().unwrap().display().to_string()
+        );
+    }
+
+    #[test]
+    fn is_builtin_recognizes_every_dispatched_name() {
+        for name in BUILTINS {
+            assert!(is_builtin(name));
+        }
+        assert!(!is_builtin("echo"));
+    }
+}
@@ -1,8 +1,13 @@
 //! Command representation.
 //!
-//! This module provides two types, [`Command`] and [`Batch`]. A command and its arguments are
-//! stored in a [`Command`] struct. A list of commands are stored in a [`Batch`]. A [`Batch`] also contains
-//! the paths to an input and/or output files if _stdin_ and/or _stdout_ have been redirected.
+//! This module provides three main types, [`Command`], [`Batch`] and [`CommandList`]. A command and
+//! its arguments are stored in a [`Command`] struct. A list of piped commands are stored in a
+//! [`Batch`], which also contains the paths to an input and/or output files if _stdin_ and/or
+//! _stdout_ have been redirected. A [`CommandList`] strings several [`Batch`]es together with
+//! `;`/`&&`/`||` operators, each wrapped in a [`Pipeline`] alongside the [`Op`] that precedes it.
+//!
+//! Parsing alone doesn't resolve `$VAR` or `$(...)` substitutions; see [`crate::expand`] for the
+//! pass that turns a [`Batch`] or [`CommandList`] into something runnable.
 //!
 //! ## Simple usage
 //!
@@ -12,19 +17,33 @@
 //! let s = "wc -l file.txt";
 //! let job = Batch::new(s);
 //! ```
-use std::{path::PathBuf, str::FromStr};
+use std::path::PathBuf;
+
+use crate::job::JobTable;
+use crate::lexer::{tokenize, Fd, RedirMode, Token, Word};
+
+/// A `>`/`>>`/`2>`/`2>>` output redirection: which file to write to and whether to truncate or
+/// append. Which stream (`stdout` or `stderr`) it applies to is recorded separately, by which of
+/// [`Batch::stdout`]/[`Batch::stderr`] holds it, rather than on the `Redirect` itself - a single
+/// command line can redirect both independently (`echo hi > out.txt 2> err.txt`), so one `Redirect`
+/// per stream is what lets both survive instead of one clobbering the other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub target: PathBuf,
+    pub mode: RedirMode,
+}
 
-/// Representation of a shell command.
+/// Representation of a shell command, not yet expanded.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Command<'a> {
+pub struct Command {
     /// Executable name.
-    cmd: &'a str,
+    pub(crate) cmd: Word,
     /// Command arguments.
-    args: Vec<&'a str>,
+    pub(crate) args: Vec<Word>,
 }
 
-impl<'a> Command<'a> {
-    pub fn new(cmd: &'a str, args: Vec<&'a str>) -> Self {
+impl Command {
+    pub fn new(cmd: Word, args: Vec<Word>) -> Self {
         Self { cmd, args }
     }
 }
@@ -32,73 +51,207 @@ impl<'a> Command<'a> {
 /// Representation of piped commands to be executed.
 /// This struct also contains the paths to the files used for input/output redirection.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Batch<'a> {
+pub struct Batch {
     /// List of commands to be executed.
-    cmds: Vec<Command<'a>>,
+    pub(crate) cmds: Vec<Command>,
     /// Path to a file to be used as input instead of `stdin`.
-    input: Option<PathBuf>,
-    /// Path to a file to be used as output instead of `stdout`.
-    output: Option<PathBuf>,
+    pub(crate) input: Option<PathBuf>,
+    /// Redirection to apply to the last command's `stdout`, if any. Independent of
+    /// [`Batch::stderr`], so both can be set at once (`> out.txt 2> err.txt`).
+    pub(crate) stdout: Option<Redirect>,
+    /// Redirection to apply to the last command's `stderr`, if any. Independent of
+    /// [`Batch::stdout`], so both can be set at once (`> out.txt 2> err.txt`).
+    pub(crate) stderr: Option<Redirect>,
     /// Flag that indicates if the commands have to be executed in the background.
-    is_async: bool,
+    pub(crate) is_async: bool,
 }
 
-impl<'a> Batch<'a> {
-    pub fn new(input: &'a str) -> Self {
-        let mut commands: Vec<Command<'a>> = Vec::new();
+impl Batch {
+    pub fn new(input: &str) -> Self {
+        Self::from_tokens(tokenize(input))
+    }
+
+    /// Builds a [`Batch`] from an already-tokenized pipeline, i.e. a token stream with no
+    /// `;`/`&&`/`||`/`&` left in it except possibly a trailing `&`; [`CommandList::new`] splits
+    /// on all four before calling this, ending the preceding pipeline at each one the same way,
+    /// so a non-trailing `&` (as in `echo a & echo b`) can never reach here.
+    ///
+    /// A trailing unquoted `&` backgrounds the whole pipeline; since the tokenizer only ever
+    /// produces a bare [`Token::Background`] for an unquoted `&` that isn't part of `&&` (see
+    /// [`crate::lexer::tokenize`]), the only thing left to check here is that it's the *last*
+    /// token.
+    fn from_tokens(mut tokens: Vec<Token>) -> Self {
+        let is_async = matches!(tokens.last(), Some(Token::Background));
+        if is_async {
+            tokens.pop();
+        }
+
+        let mut commands: Vec<Command> = Vec::new();
+        let mut current: Vec<Word> = Vec::new();
         let mut redir_in: Option<PathBuf> = None;
-        let mut redir_out: Option<PathBuf> = None;
-        let mut is_async: bool = false;
-
-        if !input.is_empty() {
-            let limit: usize;
-
-            is_async = if input.contains('&') { true } else { false };
-            if let Some(pos_in) = input.find('<') {
-                if let Some(pos_out) = input.find('>') {
-                    if pos_in > pos_out {
-                        // cat | grep .txt > output.txt < input.txt
-                        limit = pos_out;
-                        let remainder: &str = &input[limit..];
-                        let tokens: Vec<&str> = remainder.split("<").collect();
-                        redir_out = Some(PathBuf::from_str(&tokens[0][1..].trim()).unwrap());
-                        redir_in = Some(PathBuf::from_str(&tokens[1][1..].trim()).unwrap());
-                    } else {
-                        // cat | grep .txt < input.txt > output.txt
-                        limit = pos_in;
-                        let remainder: &str = &input[limit..];
-                        let tokens: Vec<&str> = remainder.split(">").collect();
-                        redir_in = Some(PathBuf::from_str(&tokens[0][1..].trim()).unwrap());
-                        redir_out = Some(PathBuf::from_str(&tokens[1][1..].trim()).unwrap());
+        let mut redir_stdout: Option<Redirect> = None;
+        let mut redir_stderr: Option<Redirect> = None;
+
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Word(word) => current.push(word),
+                Token::Pipe => push_command(&mut commands, &mut current),
+                Token::RedirectIn => {
+                    if let Some(Token::Word(word)) = tokens.next() {
+                        redir_in = Some(PathBuf::from(word.text));
                     }
-                } else {
-                    limit = pos_in;
-                    redir_in = Some(PathBuf::from_str(input[limit + 1..].trim()).unwrap());
                 }
-            } else {
-                if let Some(pos_out) = input.find('>') {
-                    limit = pos_out;
-                    redir_out = Some(PathBuf::from_str(input[limit + 1..].trim()).unwrap());
-                } else {
-                    limit = input.len();
+                Token::Redirect(fd, mode) => {
+                    if let Some(Token::Word(word)) = tokens.next() {
+                        let redirect = Redirect {
+                            target: PathBuf::from(word.text),
+                            mode,
+                        };
+                        match fd {
+                            Fd::Stdout => redir_stdout = Some(redirect),
+                            Fd::Stderr => redir_stderr = Some(redirect),
+                        }
+                    }
+                }
+                Token::Background | Token::Semicolon | Token::And | Token::Or => {
+                    unreachable!("CommandList::new splits these out before building a Batch")
                 }
-            }
-
-            for command in input[..limit].trim().split('|') {
-                let cmd_tokens: Vec<&str> = command.trim().split_whitespace().collect();
-                commands.push(Command::new(cmd_tokens[0], cmd_tokens[1..].to_vec()));
             }
         }
+        push_command(&mut commands, &mut current);
 
         Self {
             cmds: commands,
             input: redir_in,
-            output: redir_out,
+            stdout: redir_stdout,
+            stderr: redir_stderr,
             is_async,
         }
     }
 }
 
+/// The operator joining a [`Pipeline`] to the one before it in a [`CommandList`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `;`: always run, regardless of the previous pipeline's exit status.
+    Seq,
+    /// `&&`: run only if the previous pipeline succeeded.
+    And,
+    /// `||`: run only if the previous pipeline failed.
+    Or,
+}
+
+/// One [`Batch`] in a [`CommandList`], paired with the operator that precedes it. The first
+/// pipeline in a list has no real predecessor, so it's paired with [`Op::Seq`] and always runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pipeline {
+    pub(crate) op: Op,
+    pub(crate) batch: Batch,
+}
+
+/// A sequence of [`Pipeline`]s joined by `;`, `&&` or `||`, e.g. `a | b && c | d; e`. Parsed at a
+/// higher precedence than `|`, so pipe-chains stay intact within each pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandList {
+    pub(crate) pipelines: Vec<Pipeline>,
+}
+
+impl CommandList {
+    pub fn new(input: &str) -> Self {
+        let mut pipelines = Vec::new();
+        let mut current: Vec<Token> = Vec::new();
+        let mut op = Op::Seq;
+
+        for token in tokenize(input) {
+            match token {
+                Token::Semicolon => {
+                    push_pipeline(&mut pipelines, &mut current, op);
+                    op = Op::Seq;
+                }
+                Token::And => {
+                    push_pipeline(&mut pipelines, &mut current, op);
+                    op = Op::And;
+                }
+                Token::Or => {
+                    push_pipeline(&mut pipelines, &mut current, op);
+                    op = Op::Or;
+                }
+                // An unquoted `&` backgrounds the pipeline built so far and ends it, the same
+                // way `;` does, rather than backgrounding the whole command list; what follows
+                // runs unconditionally, like after a `;`. Left attached to `current` so
+                // `Batch::from_tokens` is the single place that turns a trailing `&` into
+                // `is_async` - see its doc comment.
+                Token::Background => {
+                    current.push(Token::Background);
+                    push_pipeline(&mut pipelines, &mut current, op);
+                    op = Op::Seq;
+                }
+                other => current.push(other),
+            }
+        }
+        push_pipeline(&mut pipelines, &mut current, op);
+
+        Self { pipelines }
+    }
+
+    /// Resolves `$VAR`/`$(...)` substitutions and runs the list, honoring `;`/`&&`/`||`
+    /// short-circuiting: [`Op::And`] only runs if the previous pipeline succeeded, [`Op::Or`]
+    /// only if it failed, and [`Op::Seq`] always runs. A pipeline that fails to spawn at all
+    /// (see [`crate::expand::ExpandedBatch::execute`]) counts as a failure for this purpose.
+    /// Returns the last pipeline that actually ran's exit status.
+    ///
+    /// Each pipeline is expanded against a *fresh* snapshot of the environment taken right before
+    /// it runs, rather than one snapshot for the whole list taken up front - otherwise
+    /// `export FOO=bar` wouldn't be visible to anything later on the same line, since
+    /// [`crate::builtin::run`]'s `export` mutates the real environment as a side effect of
+    /// running a pipeline, not before the list as a whole is expanded.
+    pub fn execute(&self, jobs: &mut JobTable) -> Option<std::process::ExitStatus> {
+        let mut status = None;
+        let mut succeeded = true;
+
+        for pipeline in &self.pipelines {
+            let should_run = match pipeline.op {
+                Op::Seq => true,
+                Op::And => succeeded,
+                Op::Or => !succeeded,
+            };
+            if !should_run {
+                continue;
+            }
+
+            status = pipeline.batch.expand(&std::env::vars().collect()).execute(jobs);
+            succeeded = status.as_ref().is_some_and(std::process::ExitStatus::success);
+        }
+
+        status
+    }
+}
+
+/// Turns the words collected for the command currently being built into a [`Command`] and
+/// pushes it onto `commands`, leaving `current` empty. A no-op if `current` is empty, so that
+/// e.g. a trailing `|` with nothing after it doesn't produce a bogus empty command.
+fn push_command(commands: &mut Vec<Command>, current: &mut Vec<Word>) {
+    if current.is_empty() {
+        return;
+    }
+    let mut words = std::mem::take(current).into_iter();
+    let cmd = words.next().unwrap();
+    commands.push(Command::new(cmd, words.collect()));
+}
+
+/// Turns the tokens collected for the pipeline currently being built into a [`Pipeline`] and
+/// pushes it onto `pipelines`, leaving `current` empty. A no-op if `current` is empty, so that
+/// e.g. a trailing `;` with nothing after it doesn't produce a bogus empty pipeline.
+fn push_pipeline(pipelines: &mut Vec<Pipeline>, current: &mut Vec<Token>, op: Op) {
+    if current.is_empty() {
+        return;
+    }
+    let batch = Batch::from_tokens(std::mem::take(current));
+    let op = if pipelines.is_empty() { Op::Seq } else { op };
+    pipelines.push(Pipeline { op, batch });
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,15 +264,16 @@ mod tests {
     }
 
     #[test]
-    fn single_command_without_arguments<'a>() {
+    fn single_command_without_arguments() {
         let s = "echo";
-        let args: Vec<&'a str> = Vec::new();
+        let args: Vec<Word> = Vec::new();
         let job = Batch::new(s);
         assert_eq!(job.cmds.len(), 1);
         assert_eq!(job.cmds[0].cmd, "echo");
         assert_eq!(job.cmds[0].args, args);
         assert_eq!(job.input, None);
-        assert_eq!(job.output, None);
+        assert_eq!(job.stdout, None);
+        assert_eq!(job.stderr, None);
     }
 
     #[test]
@@ -130,7 +284,8 @@ mod tests {
         assert_eq!(job.cmds[0].cmd, "wc");
         assert_eq!(job.cmds[0].args, vec!["-l", "file.txt"]);
         assert_eq!(job.input, None);
-        assert_eq!(job.output, None);
+        assert_eq!(job.stdout, None);
+        assert_eq!(job.stderr, None);
     }
 
     #[test]
@@ -143,7 +298,8 @@ mod tests {
         assert_eq!(job.cmds[1].cmd, "wc");
         assert_eq!(job.cmds[1].args, vec!["-l"]);
         assert_eq!(job.input, None);
-        assert_eq!(job.output, None);
+        assert_eq!(job.stdout, None);
+        assert_eq!(job.stderr, None);
     }
 
     #[test]
@@ -154,7 +310,8 @@ mod tests {
         assert_eq!(job.cmds[0].cmd, "cat");
         assert_eq!(job.input.unwrap().as_os_str(), "input.txt");
         assert!(job.cmds[0].args.is_empty());
-        assert_eq!(job.output, None);
+        assert_eq!(job.stdout, None);
+        assert_eq!(job.stderr, None);
     }
 
     #[test]
@@ -163,7 +320,7 @@ mod tests {
         let job = Batch::new(s);
         assert_eq!(job.cmds.len(), 1);
         assert_eq!(job.cmds[0].cmd, "cat");
-        assert_eq!(job.output.unwrap().as_os_str(), "output.txt");
+        assert_eq!(job.stdout.unwrap().target.as_os_str(), "output.txt");
         assert_eq!(job.cmds[0].args, vec!["input.txt"]);
         assert_eq!(job.input, None);
     }
@@ -175,7 +332,7 @@ mod tests {
         assert_eq!(job.cmds.len(), 1);
         assert_eq!(job.cmds[0].cmd, "cat");
         assert_eq!(job.input.unwrap().as_os_str(), "input.txt");
-        assert_eq!(job.output.unwrap().as_os_str(), "output.txt");
+        assert_eq!(job.stdout.unwrap().target.as_os_str(), "output.txt");
         assert!(job.cmds[0].args.is_empty());
     }
 
@@ -186,7 +343,199 @@ mod tests {
         assert_eq!(job.cmds.len(), 1);
         assert_eq!(job.cmds[0].cmd, "cat");
         assert_eq!(job.input.unwrap().as_os_str(), "input.txt");
-        assert_eq!(job.output.unwrap().as_os_str(), "output.txt");
+        assert_eq!(job.stdout.unwrap().target.as_os_str(), "output.txt");
         assert!(job.cmds[0].args.is_empty());
     }
+
+    #[test]
+    fn quoted_argument_with_spaces_stays_together() {
+        let s = r#"echo "hello world""#;
+        let job = Batch::new(s);
+        assert_eq!(job.cmds.len(), 1);
+        assert_eq!(job.cmds[0].cmd, "echo");
+        assert_eq!(job.cmds[0].args, vec!["hello world"]);
+    }
+
+    #[test]
+    fn single_quoted_pipe_is_not_a_redirection() {
+        let s = "grep 'a | b' file.txt";
+        let job = Batch::new(s);
+        assert_eq!(job.cmds.len(), 1);
+        assert_eq!(job.cmds[0].cmd, "grep");
+        assert_eq!(job.cmds[0].args, vec!["a | b", "file.txt"]);
+    }
+
+    #[test]
+    fn quoted_redirect_operator_is_not_a_redirection() {
+        let s = r#"echo "a > b""#;
+        let job = Batch::new(s);
+        assert_eq!(job.cmds.len(), 1);
+        assert_eq!(job.cmds[0].args, vec!["a > b"]);
+        assert_eq!(job.stdout, None);
+    }
+
+    #[test]
+    fn path_with_spaces_can_be_redirected_to() {
+        let s = r#"echo hi > "my file.txt""#;
+        let job = Batch::new(s);
+        assert_eq!(job.stdout.unwrap().target.as_os_str(), "my file.txt");
+    }
+
+    #[test]
+    fn double_angle_bracket_appends_to_stdout() {
+        let s = "echo hi >> log.txt";
+        let job = Batch::new(s);
+        let redirect = job.stdout.unwrap();
+        assert_eq!(redirect.target.as_os_str(), "log.txt");
+        assert_eq!(redirect.mode, RedirMode::Append);
+        assert_eq!(job.stderr, None);
+    }
+
+    #[test]
+    fn two_angle_bracket_redirects_stderr() {
+        let s = "cmd 2> errors.txt";
+        let job = Batch::new(s);
+        let redirect = job.stderr.unwrap();
+        assert_eq!(redirect.target.as_os_str(), "errors.txt");
+        assert_eq!(redirect.mode, RedirMode::Truncate);
+        assert_eq!(job.stdout, None);
+    }
+
+    #[test]
+    fn two_double_angle_bracket_appends_to_stderr() {
+        let s = "cmd 2>> errors.txt";
+        let job = Batch::new(s);
+        let redirect = job.stderr.unwrap();
+        assert_eq!(redirect.mode, RedirMode::Append);
+    }
+
+    #[test]
+    fn independent_stdout_and_stderr_redirects_both_survive() {
+        let s = "echo hi > out.txt 2> err.txt";
+        let job = Batch::new(s);
+        assert_eq!(job.stdout.unwrap().target.as_os_str(), "out.txt");
+        assert_eq!(job.stderr.unwrap().target.as_os_str(), "err.txt");
+    }
+
+    #[test]
+    fn execute_empty_batch_is_a_noop() {
+        let job = CommandList::new("");
+        assert_eq!(job.execute(&mut JobTable::new()), None);
+    }
+
+    #[test]
+    fn execute_single_command() {
+        let job = CommandList::new("true");
+        let status = job.execute(&mut JobTable::new()).expect("true should spawn");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn execute_reports_failing_exit_status() {
+        let job = CommandList::new("false");
+        let status = job.execute(&mut JobTable::new()).expect("false should spawn");
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn execute_pipeline_returns_last_status() {
+        let job = CommandList::new("true | false");
+        let status = job.execute(&mut JobTable::new()).expect("pipeline should spawn");
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn execute_missing_command_does_not_panic() {
+        let job = CommandList::new("this-command-does-not-exist-1234");
+        assert_eq!(job.execute(&mut JobTable::new()), None);
+    }
+
+    #[test]
+    fn single_pipeline_has_no_operator_before_it() {
+        let list = CommandList::new("echo hi");
+        assert_eq!(list.pipelines.len(), 1);
+        assert_eq!(list.pipelines[0].op, Op::Seq);
+        assert_eq!(list.pipelines[0].batch.cmds[0].cmd, "echo");
+    }
+
+    #[test]
+    fn semicolon_separated_pipelines_are_split() {
+        let list = CommandList::new("echo a; echo b");
+        assert_eq!(list.pipelines.len(), 2);
+        assert_eq!(list.pipelines[0].op, Op::Seq);
+        assert_eq!(list.pipelines[1].op, Op::Seq);
+        assert_eq!(list.pipelines[0].batch.cmds[0].args, vec!["a"]);
+        assert_eq!(list.pipelines[1].batch.cmds[0].args, vec!["b"]);
+    }
+
+    #[test]
+    fn and_and_or_operators_are_recorded_per_pipeline() {
+        let list = CommandList::new("echo a && echo b || echo c");
+        assert_eq!(list.pipelines.len(), 3);
+        assert_eq!(list.pipelines[0].op, Op::Seq);
+        assert_eq!(list.pipelines[1].op, Op::And);
+        assert_eq!(list.pipelines[2].op, Op::Or);
+    }
+
+    #[test]
+    fn pipe_binds_tighter_than_the_list_operators() {
+        let list = CommandList::new("a | b && c | d");
+        assert_eq!(list.pipelines.len(), 2);
+        assert_eq!(list.pipelines[0].batch.cmds.len(), 2);
+        assert_eq!(list.pipelines[1].batch.cmds.len(), 2);
+        assert_eq!(list.pipelines[1].op, Op::And);
+    }
+
+    #[test]
+    fn trailing_separator_does_not_produce_an_empty_pipeline() {
+        let list = CommandList::new("echo hi;");
+        assert_eq!(list.pipelines.len(), 1);
+    }
+
+    #[test]
+    fn trailing_ampersand_backgrounds_the_pipeline() {
+        let job = Batch::new("sleep 1 &");
+        assert!(job.is_async);
+        assert_eq!(job.cmds[0].cmd, "sleep");
+        assert_eq!(job.cmds[0].args, vec!["1"]);
+    }
+
+    #[test]
+    fn ampersand_inside_double_ampersand_does_not_background() {
+        let list = CommandList::new("sleep 1 && echo done");
+        assert!(!list.pipelines[0].batch.is_async);
+        assert!(!list.pipelines[1].batch.is_async);
+    }
+
+    #[test]
+    fn quoted_ampersand_does_not_background() {
+        let job = Batch::new(r#"echo "a & b""#);
+        assert!(!job.is_async);
+    }
+
+    #[test]
+    fn export_takes_effect_for_later_pipelines_on_the_same_line() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rash-export-test-{:?}", std::thread::current().id()));
+
+        let list = CommandList::new(&format!(
+            "export RASH_EXPORT_PROPAGATION_TEST=hello; echo $RASH_EXPORT_PROPAGATION_TEST > {}",
+            path.display()
+        ));
+        list.execute(&mut JobTable::new());
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "hello\n");
+    }
+
+    #[test]
+    fn non_trailing_ampersand_ends_the_pipeline_instead_of_being_dropped() {
+        let list = CommandList::new("echo a & echo b");
+        assert_eq!(list.pipelines.len(), 2);
+        assert!(list.pipelines[0].batch.is_async);
+        assert_eq!(list.pipelines[0].batch.cmds[0].args, vec!["a"]);
+        assert!(!list.pipelines[1].batch.is_async);
+        assert_eq!(list.pipelines[1].batch.cmds[0].args, vec!["b"]);
+    }
 }
@@ -0,0 +1,551 @@
+//! Expansion of `$VAR`, `${VAR}` and `$(...)`/backtick substitutions.
+//!
+//! [`Batch::expand`] walks every [`Command`]'s `cmd` and args and resolves them against a
+//! snapshot of the environment, producing an [`ExpandedBatch`] of plain, owned strings that the
+//! execution path can hand straight to [`std::process::Command`].
+//!
+//! Expansion is disabled for a [`Word`] built entirely from single-quoted text (it is `literal`
+//! and passed through untouched) and enabled for every other word. A word that mixes
+//! single-quoted text with unquoted or double-quoted text is expanded as a whole, so `$` inside
+//! its single-quoted part is substituted too (`a'$HOME'` expands `$HOME`). Double quotes still
+//! suppress word-splitting of the expanded value, so `"$(echo a b)"` is one argument but
+//! `$(echo a b)` is two.
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{Child, ExitStatus, Stdio},
+};
+
+use crate::builtin;
+use crate::command::{Batch, Command, CommandList, Redirect};
+use crate::job::JobTable;
+use crate::lexer::{RedirMode, Word};
+
+/// A fully-resolved command, ready to be spawned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedCommand {
+    pub cmd: String,
+    pub args: Vec<String>,
+}
+
+/// A [`Batch`] with every `$VAR`/`$(...)` substitution resolved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedBatch {
+    pub cmds: Vec<ExpandedCommand>,
+    pub input: Option<PathBuf>,
+    pub stdout: Option<Redirect>,
+    pub stderr: Option<Redirect>,
+    pub is_async: bool,
+}
+
+impl Batch {
+    /// Resolves `$VAR`/`${VAR}` against `env` and runs any `$(...)`/backtick substitution,
+    /// returning a [`ExpandedBatch`] the execution path can run directly.
+    pub fn expand(&self, env: &HashMap<String, String>) -> ExpandedBatch {
+        ExpandedBatch {
+            cmds: self.cmds.iter().map(|cmd| expand_command(cmd, env)).collect(),
+            input: self.input.clone(),
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            is_async: self.is_async,
+        }
+    }
+}
+
+impl ExpandedBatch {
+    /// Runs [`ExpandedBatch::cmds`] as a connected pipeline. The first command reads from
+    /// [`ExpandedBatch::input`] and the last writes to [`ExpandedBatch::stdout`] and
+    /// [`ExpandedBatch::stderr`] where those are set. If a redirect target can't be opened or a
+    /// command can't be spawned, an error is printed, the stages already spawned are killed, and
+    /// `None` is returned. If
+    /// [`ExpandedBatch::is_async`] is set, the pipeline is spawned and registered with `jobs`
+    /// instead of being waited on, and the returned status is a synthetic success reporting only
+    /// that it was launched.
+    pub fn execute(&self, jobs: &mut JobTable) -> Option<ExitStatus> {
+        self.run(false, jobs).0
+    }
+
+    /// Like [`ExpandedBatch::execute`], but captures the last command's `stdout` instead of
+    /// writing it to [`ExpandedBatch::stdout`] or inheriting it. Used to resolve `$(...)` and
+    /// backtick substitutions.
+    pub fn capture(&self, jobs: &mut JobTable) -> Option<Vec<u8>> {
+        self.run(true, jobs).1
+    }
+
+    fn run(&self, capture: bool, jobs: &mut JobTable) -> (Option<ExitStatus>, Option<Vec<u8>>) {
+        if self.cmds.is_empty() {
+            return (None, None);
+        }
+
+        // Builtins run in-process rather than as a spawned `Child`, so they'd otherwise bypass
+        // the `Stdio` wiring below entirely and always print to the real terminal - checked by
+        // name first so a non-builtin single command (e.g. `echo`) doesn't pay for opening
+        // `self.stdout` here only to have the external-spawn path below open it again. Only a
+        // lone command outside a `$(...)` capture is dispatched this way; a builtin name inside a
+        // multi-command pipeline or a substitution is spawned externally like any other. A
+        // builtin's error messages always go to the shell's own `stderr`: `self.stderr` is
+        // neither opened nor applied on this path.
+        if !capture && self.cmds.len() == 1 && builtin::is_builtin(&self.cmds[0].cmd) {
+            let mut stdout: Box<dyn Write> = match &self.stdout {
+                Some(redirect) => match open_redirect(redirect) {
+                    Ok(file) => Box::new(file),
+                    Err(err) => {
+                        eprintln!("rash: {}: {}", redirect.target.display(), err);
+                        return (None, None);
+                    }
+                },
+                None => Box::new(io::stdout()),
+            };
+            if let Some(status) = builtin::run(&self.cmds[0], jobs, &mut stdout) {
+                return (Some(status), None);
+            }
+        }
+
+        let last = self.cmds.len() - 1;
+        let mut children: Vec<Child> = Vec::with_capacity(self.cmds.len());
+
+        for (i, command) in self.cmds.iter().enumerate() {
+            let mut process = std::process::Command::new(&command.cmd);
+            process.args(&command.args);
+
+            process.stdin(match children.last_mut() {
+                Some(prev) => Stdio::from(prev.stdout.take().unwrap()),
+                None => match &self.input {
+                    Some(path) => match File::open(path) {
+                        Ok(file) => Stdio::from(file),
+                        Err(err) => {
+                            eprintln!("rash: {}: {}", path.display(), err);
+                            return abort(children);
+                        }
+                    },
+                    None => Stdio::inherit(),
+                },
+            });
+
+            process.stdout(if i != last || capture {
+                Stdio::piped()
+            } else {
+                match &self.stdout {
+                    Some(redirect) => match open_redirect(redirect) {
+                        Ok(file) => Stdio::from(file),
+                        Err(err) => {
+                            eprintln!("rash: {}: {}", redirect.target.display(), err);
+                            return abort(children);
+                        }
+                    },
+                    None => Stdio::inherit(),
+                }
+            });
+
+            if i == last {
+                if let Some(redirect) = &self.stderr {
+                    match open_redirect(redirect) {
+                        Ok(file) => {
+                            process.stderr(file);
+                        }
+                        Err(err) => {
+                            eprintln!("rash: {}: {}", redirect.target.display(), err);
+                            return abort(children);
+                        }
+                    }
+                }
+            }
+
+            match process.spawn() {
+                Ok(child) => children.push(child),
+                Err(err) => {
+                    eprintln!("rash: {}: {}", command.cmd, err);
+                    return abort(children);
+                }
+            }
+        }
+
+        if self.is_async {
+            jobs.spawn(self.describe(), children);
+            return (Some(ExitStatus::from_raw(0)), None);
+        }
+
+        let total = children.len();
+        let mut status = None;
+        let mut captured = None;
+        for (i, mut child) in children.into_iter().enumerate() {
+            if capture && i == total - 1 {
+                let output = child.wait_with_output().ok();
+                status = output.as_ref().map(|output| output.status);
+                captured = output.map(|output| output.stdout);
+            } else {
+                status = child.wait().ok();
+            }
+        }
+        (status, captured)
+    }
+
+    /// Renders the pipeline back into roughly the shell syntax it came from, for display in
+    /// `jobs`. Not a faithful round-trip (expansion has already happened, and redirections aren't
+    /// shown), just enough to recognize which command a job is.
+    fn describe(&self) -> String {
+        self.cmds
+            .iter()
+            .map(|cmd| {
+                if cmd.args.is_empty() {
+                    cmd.cmd.clone()
+                } else {
+                    format!("{} {}", cmd.cmd, cmd.args.join(" "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+}
+
+/// Kills and reaps every already-spawned stage of a pipeline that failed to finish being built (a
+/// later stage's redirect or spawn failed), so they don't leak as orphaned processes that aren't
+/// in the [`JobTable`] for `jobs`/`wait` to find. Always reports the pipeline as not having run.
+fn abort(children: Vec<Child>) -> (Option<ExitStatus>, Option<Vec<u8>>) {
+    for mut child in children {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+    (None, None)
+}
+
+/// Opens a [`Redirect`]'s target, truncating or appending per its [`RedirMode`].
+fn open_redirect(redirect: &Redirect) -> io::Result<File> {
+    match redirect.mode {
+        RedirMode::Truncate => File::create(&redirect.target),
+        RedirMode::Append => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&redirect.target),
+    }
+}
+
+fn expand_command(command: &Command, env: &HashMap<String, String>) -> ExpandedCommand {
+    let mut words = expand_word(&command.cmd, env).into_iter();
+    let cmd = words.next().unwrap_or_default();
+    let mut args: Vec<String> = words.collect();
+    for arg in &command.args {
+        args.extend(expand_word(arg, env));
+    }
+    ExpandedCommand { cmd, args }
+}
+
+/// Expands a single [`Word`] into zero or more argument strings: `literal` words (fully
+/// single-quoted) pass through untouched, `quoted` words expand to exactly one string, and bare
+/// words are word-split on whitespace after expansion.
+fn expand_word(word: &Word, env: &HashMap<String, String>) -> Vec<String> {
+    if word.literal {
+        return vec![word.text.clone()];
+    }
+
+    let substituted = substitute(&word.text, env);
+    if word.quoted {
+        vec![substituted]
+    } else {
+        substituted.split_whitespace().map(String::from).collect()
+    }
+}
+
+/// Resolves `$VAR`, `${VAR}` and `$(...)`/backtick substitutions in `text`.
+fn substitute(text: &str, env: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => match chars.peek() {
+                Some('{') => {
+                    chars.next();
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    result.push_str(env.get(&name).map_or("", String::as_str));
+                }
+                Some('(') => {
+                    chars.next();
+                    let inner = take_balanced(&mut chars, '(', ')');
+                    result.push_str(&run_substitution(&inner, env));
+                }
+                Some(&c) if c == '_' || c.is_alphabetic() => {
+                    let mut name = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == '_' || c.is_alphanumeric() {
+                            name.push(c);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    result.push_str(env.get(&name).map_or("", String::as_str));
+                }
+                _ => result.push('$'),
+            },
+            '`' => {
+                let inner: String = chars.by_ref().take_while(|&c| c != '`').collect();
+                result.push_str(&run_substitution(&inner, env));
+            }
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Consumes characters up to (and including) the `close` that balances the `open` already
+/// consumed, so that nested `$(...)` substitutions are captured whole.
+fn take_balanced(chars: &mut std::iter::Peekable<std::str::Chars>, open: char, close: char) -> String {
+    let mut depth = 1;
+    let mut inner = String::new();
+    for c in chars.by_ref() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        }
+        inner.push(c);
+    }
+    inner
+}
+
+/// Runs `cmd_str` as a nested [`Batch`], capturing its `stdout` and trimming a single trailing
+/// newline, as `$(...)`/backtick substitution requires. Any background job the substitution
+/// itself spawns is tracked in a throwaway [`JobTable`] scoped to this call, rather than the
+/// shell's own - there's no sensible way to `jobs`/`wait` on it from the surrounding command line.
+fn run_substitution(cmd_str: &str, env: &HashMap<String, String>) -> String {
+    let mut scratch_jobs = JobTable::new();
+    let output = Batch::new(cmd_str)
+        .expand(env)
+        .capture(&mut scratch_jobs)
+        .unwrap_or_default();
+    let text = String::from_utf8_lossy(&output).into_owned();
+    text.strip_suffix('\n').unwrap_or(&text).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn expands_a_simple_variable() {
+        let batch = Batch::new("echo $NAME");
+        let expanded = batch.expand(&env(&[("NAME", "world")]));
+        assert_eq!(expanded.cmds[0].args, vec!["world"]);
+    }
+
+    #[test]
+    fn expands_a_braced_variable() {
+        let batch = Batch::new("echo ${NAME}!");
+        let expanded = batch.expand(&env(&[("NAME", "world")]));
+        assert_eq!(expanded.cmds[0].args, vec!["world!"]);
+    }
+
+    #[test]
+    fn unset_variable_expands_to_empty_string() {
+        let batch = Batch::new("echo $MISSING");
+        let expanded = batch.expand(&env(&[]));
+        assert!(expanded.cmds[0].args.is_empty());
+    }
+
+    #[test]
+    fn single_quotes_disable_expansion() {
+        let batch = Batch::new("echo '$NAME'");
+        let expanded = batch.expand(&env(&[("NAME", "world")]));
+        assert_eq!(expanded.cmds[0].args, vec!["$NAME"]);
+    }
+
+    #[test]
+    fn double_quotes_still_expand() {
+        let batch = Batch::new(r#"echo "hi $NAME""#);
+        let expanded = batch.expand(&env(&[("NAME", "world")]));
+        assert_eq!(expanded.cmds[0].args, vec!["hi world"]);
+    }
+
+    #[test]
+    fn unquoted_expansion_is_word_split() {
+        let batch = Batch::new("echo $LIST");
+        let expanded = batch.expand(&env(&[("LIST", "a b c")]));
+        assert_eq!(expanded.cmds[0].args, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn quoted_expansion_is_not_word_split() {
+        let batch = Batch::new(r#"echo "$LIST""#);
+        let expanded = batch.expand(&env(&[("LIST", "a b c")]));
+        assert_eq!(expanded.cmds[0].args, vec!["a b c"]);
+    }
+
+    #[test]
+    fn command_substitution_runs_the_inner_command() {
+        let batch = Batch::new("echo $(echo hi)");
+        let expanded = batch.expand(&env(&[]));
+        assert_eq!(expanded.cmds[0].args, vec!["hi"]);
+    }
+
+    #[test]
+    fn quoted_command_substitution_is_not_split() {
+        let batch = Batch::new(r#"echo "$(printf 'a b')""#);
+        let expanded = batch.expand(&env(&[]));
+        assert_eq!(expanded.cmds[0].args, vec!["a b"]);
+    }
+
+    #[test]
+    fn backtick_command_substitution_is_supported() {
+        let batch = Batch::new("echo `echo hi`");
+        let expanded = batch.expand(&env(&[]));
+        assert_eq!(expanded.cmds[0].args, vec!["hi"]);
+    }
+
+    #[test]
+    fn execute_runs_the_expanded_pipeline() {
+        let expanded = Batch::new("true").expand(&env(&[]));
+        let status = expanded.execute(&mut JobTable::new()).expect("true should spawn");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn append_redirection_keeps_prior_content() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rash-append-test-{:?}", std::thread::current().id()));
+        std::fs::write(&path, "first\n").unwrap();
+
+        let batch = Batch::new(&format!("echo second >> {}", path.display()));
+        batch
+            .expand(&env(&[]))
+            .execute(&mut JobTable::new())
+            .expect("echo should spawn");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "first\nsecond\n");
+    }
+
+    #[test]
+    fn stderr_redirection_leaves_stdout_alone() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rash-stderr-test-{:?}", std::thread::current().id()));
+
+        let batch = Batch::new(&format!("echo hi 2> {}", path.display()));
+        batch
+            .expand(&env(&[]))
+            .execute(&mut JobTable::new())
+            .expect("echo should spawn");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "");
+    }
+
+    #[test]
+    fn stdout_and_stderr_redirects_both_take_effect() {
+        let dir = std::env::temp_dir();
+        let id = format!("{:?}", std::thread::current().id());
+        let out_path = dir.join(format!("rash-both-redirect-out-{id}"));
+        let err_path = dir.join(format!("rash-both-redirect-err-{id}"));
+
+        let batch = Batch::new(&format!(
+            "sh -c 'echo out; echo err >&2' > {} 2> {}",
+            out_path.display(),
+            err_path.display()
+        ));
+        batch
+            .expand(&env(&[]))
+            .execute(&mut JobTable::new())
+            .expect("sh should spawn");
+
+        let out_contents = std::fs::read_to_string(&out_path).unwrap();
+        let err_contents = std::fs::read_to_string(&err_path).unwrap();
+        std::fs::remove_file(&out_path).unwrap();
+        std::fs::remove_file(&err_path).unwrap();
+        assert_eq!(out_contents, "out\n");
+        assert_eq!(err_contents, "err\n");
+    }
+
+    #[test]
+    fn semicolon_runs_both_pipelines_regardless_of_status() {
+        let list = CommandList::new("false; true");
+        let status = list.execute(&mut JobTable::new()).expect("true should spawn");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn and_skips_the_next_pipeline_after_a_failure() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rash-and-test-{:?}", std::thread::current().id()));
+        std::fs::remove_file(&path).ok();
+
+        let list = CommandList::new(&format!("false && touch {}", path.display()));
+        list.execute(&mut JobTable::new());
+
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn or_runs_the_next_pipeline_only_after_a_failure() {
+        let list = CommandList::new("false || true");
+        let status = list.execute(&mut JobTable::new()).expect("true should spawn");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn or_is_skipped_after_success() {
+        let list = CommandList::new("true || false");
+        let status = list.execute(&mut JobTable::new()).expect("true should spawn");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn failed_spawn_does_not_orphan_earlier_pipeline_stages() {
+        let expanded =
+            Batch::new("sleep 5 | this-command-does-not-exist-xyz").expand(&env(&[]));
+        let started = std::time::Instant::now();
+        let status = expanded.execute(&mut JobTable::new());
+        assert_eq!(status, None);
+        assert!(
+            started.elapsed() < std::time::Duration::from_secs(5),
+            "earlier stage should have been killed instead of waited out"
+        );
+    }
+
+    #[test]
+    fn trailing_ampersand_backgrounds_without_blocking() {
+        let expanded = Batch::new("sleep 5 &").expand(&env(&[]));
+        let mut jobs = JobTable::new();
+        let status = expanded.execute(&mut jobs).expect("background launch should report success");
+        assert!(status.success());
+        assert_eq!(jobs.list().len(), 1);
+        jobs.wait(None);
+    }
+
+    #[test]
+    fn jobs_builtin_is_intercepted_before_spawning_externally() {
+        let expanded = Batch::new("jobs").expand(&env(&[]));
+        let status = expanded
+            .execute(&mut JobTable::new())
+            .expect("jobs is a builtin");
+        assert!(status.success());
+    }
+
+    #[test]
+    fn builtin_stdout_honors_a_redirect() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("rash-pwd-redirect-test-{:?}", std::thread::current().id()));
+
+        let batch = Batch::new(&format!("pwd > {}", path.display()));
+        batch
+            .expand(&env(&[]))
+            .execute(&mut JobTable::new())
+            .expect("pwd is a builtin");
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents.trim_end(), std::env::current_dir().unwrap().display().to_string());
+    }
+}
@@ -1,38 +1,109 @@
-use std::path::PathBuf;
+//! Background job tracking.
+//!
+//! A [`JobTable`] records the [`std::process::Child`] handles spawned for a `&`-terminated
+//! pipeline (see [`crate::command::Batch::is_async`]) along with the command text it was started
+//! from, so the `jobs` and `wait` builtins (see [`crate::builtin`]) can report on and block on
+//! them later. Jobs are identified by small, monotonically increasing ids that are never reused,
+//! even after the job they named has finished and been reaped.
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Command<'a> {
-    pub cmd: String,
-    pub args: Vec<&'a str>,
+use std::process::Child;
+
+/// Whether a [`Job`]'s pipeline is still running or has finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Done,
+}
+
+/// A background job: the [`Child`] handle for every process in its pipeline, plus the command
+/// text it was started from, for display in `jobs`.
+pub struct Job {
+    pub id: u32,
+    pub command: String,
+    children: Vec<Child>,
 }
 
-impl<'a> Command<'a> {
-    pub fn new(cmd: String, args: Vec<&'a str>) -> Self {
-        Self { cmd, args }
+impl Job {
+    /// Polls every process in the pipeline with `try_wait`, without blocking.
+    fn status(&mut self) -> JobStatus {
+        for child in &mut self.children {
+            if matches!(child.try_wait(), Ok(None)) {
+                return JobStatus::Running;
+            }
+        }
+        JobStatus::Done
+    }
+
+    /// Blocks until every process in the pipeline has exited.
+    fn wait(&mut self) {
+        for child in &mut self.children {
+            let _ = child.wait();
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Job<'a> {
-    pub cmds: Vec<Command<'a>>,
-    pub input: Option<PathBuf>,
-    pub output: Option<PathBuf>,
+/// Tracks every background job started with a trailing `&`, in the order they were started.
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+    next_id: u32,
 }
 
-impl<'a> Job<'a> {
-    pub fn new(input: &'a str) -> Self {
-        let mut commands: Vec<Command<'a>> = Vec::new();
-        if !input.is_empty() {
-            for command in input.trim().split('|') {
-                let tokens: Vec<&str> = command.trim().split_whitespace().collect();
-                commands.push(Command::new(tokens[0].to_string(), tokens[1..].to_vec()));
+impl JobTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a freshly spawned, not-yet-waited-on pipeline as a new background job and
+    /// returns its id.
+    pub fn spawn(&mut self, command: String, children: Vec<Child>) -> u32 {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.jobs.push(Job { id, command, children });
+        id
+    }
+
+    /// Lists every tracked job with its current status, polling each one.
+    pub fn list(&mut self) -> Vec<(u32, &str, JobStatus)> {
+        self.jobs
+            .iter_mut()
+            .map(|job| {
+                let status = job.status();
+                (job.id, job.command.as_str(), status)
+            })
+            .collect()
+    }
+
+    /// Removes every job that has finished, returning them so the caller can print a completion
+    /// notice for each. Meant to be called once per prompt iteration.
+    pub fn reap(&mut self) -> Vec<Job> {
+        let mut finished = Vec::new();
+        let mut i = 0;
+        while i < self.jobs.len() {
+            if self.jobs[i].status() == JobStatus::Done {
+                finished.push(self.jobs.remove(i));
+            } else {
+                i += 1;
             }
         }
+        finished
+    }
 
-        Self {
-            cmds: commands,
-            input: None,
-            output: None,
+    /// Blocks until the job with the given id (or, if `None`, every tracked job) has finished,
+    /// removing it from the table. Silently does nothing if `id` doesn't name a tracked job.
+    pub fn wait(&mut self, id: Option<u32>) {
+        match id {
+            Some(id) => {
+                if let Some(pos) = self.jobs.iter().position(|job| job.id == id) {
+                    let mut job = self.jobs.remove(pos);
+                    job.wait();
+                }
+            }
+            None => {
+                for mut job in self.jobs.drain(..) {
+                    job.wait();
+                }
+            }
         }
     }
 }
@@ -40,41 +111,67 @@ impl<'a> Job<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::process::Command;
+
+    fn spawn_sleep() -> Child {
+        Command::new("sleep").arg("5").spawn().unwrap()
+    }
+
+    fn spawn_true() -> Child {
+        Command::new("true").spawn().unwrap()
+    }
+
+    #[test]
+    fn job_ids_are_monotonically_increasing() {
+        let mut jobs = JobTable::new();
+        let first = jobs.spawn("true".to_string(), vec![spawn_true()]);
+        let second = jobs.spawn("true".to_string(), vec![spawn_true()]);
+        assert_eq!(first, 1);
+        assert_eq!(second, 2);
+        jobs.wait(None);
+    }
 
     #[test]
-    fn empty_line() {
-        let s = "";
-        let job = Job::new(s);
-        assert_eq!(job.cmds, vec![]);
+    fn list_reports_a_running_job() {
+        let mut jobs = JobTable::new();
+        jobs.spawn("sleep 5".to_string(), vec![spawn_sleep()]);
+        let listed = jobs.list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].2, JobStatus::Running);
+        jobs.wait(None);
     }
 
     #[test]
-    fn single_command_without_arguments<'a>() {
-        let s = "echo";
-        let args: Vec<&'a str> = Vec::new();
-        let job = Job::new(s);
-        assert_eq!(job.cmds.len(), 1);
-        assert_eq!(job.cmds[0].cmd, "echo");
-        assert_eq!(job.cmds[0].args, args);
+    fn reap_removes_finished_jobs_but_not_running_ones() {
+        let mut jobs = JobTable::new();
+        let done_id = jobs.spawn("true".to_string(), vec![spawn_true()]);
+        jobs.spawn("sleep 5".to_string(), vec![spawn_sleep()]);
+
+        // Give the `true` child a moment to actually exit before polling it.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let finished = jobs.reap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].id, done_id);
+        assert_eq!(jobs.list().len(), 1);
+        jobs.wait(None);
     }
 
     #[test]
-    fn single_command_with_arguments() {
-        let s = "wc -l file.txt";
-        let job = Job::new(s);
-        assert_eq!(job.cmds.len(), 1);
-        assert_eq!(job.cmds[0].cmd, "wc");
-        assert_eq!(job.cmds[0].args, vec!["-l", "file.txt"]);
+    fn wait_with_no_id_blocks_on_every_job() {
+        let mut jobs = JobTable::new();
+        jobs.spawn("true".to_string(), vec![spawn_true()]);
+        jobs.spawn("true".to_string(), vec![spawn_true()]);
+        jobs.wait(None);
+        assert_eq!(jobs.jobs.len(), 0);
     }
 
     #[test]
-    fn two_piped_commands() {
-        let s = "cat file.txt | wc -l";
-        let job = Job::new(s);
-        assert_eq!(job.cmds.len(), 2);
-        assert_eq!(job.cmds[0].cmd, "cat");
-        assert_eq!(job.cmds[0].args, vec!["file.txt"]);
-        assert_eq!(job.cmds[1].cmd, "wc");
-        assert_eq!(job.cmds[1].args, vec!["-l"]);
+    fn wait_with_an_unknown_id_does_nothing() {
+        let mut jobs = JobTable::new();
+        jobs.spawn("true".to_string(), vec![spawn_true()]);
+        jobs.wait(Some(999));
+        assert_eq!(jobs.list().len(), 1);
+        jobs.wait(None);
     }
 }
@@ -0,0 +1,472 @@
+//! Tokenizer for shell input lines.
+//!
+//! [`tokenize`] walks an input line char-by-char, tracking quote and escape state so that
+//! whitespace, `|`, `<`, `>`, `&`, `;`, `&&` and `||` are only treated as delimiters when they
+//! appear unquoted.
+//! Quoted arguments (and paths containing spaces) therefore round-trip correctly instead of
+//! being split apart by a naive [`str::split_whitespace`]. `$(...)` and `` `...` `` spans are
+//! also kept whole even when unquoted, so a command substitution's internal whitespace doesn't
+//! split it into several words before [`crate::expand`] gets a chance to run it.
+//!
+//! Each resulting [`Word`] also records whether it was single-quoted and whether it was quoted
+//! at all, so that the expansion pass knows whether `$VAR`/`$(...)` substitution applies to it
+//! and whether its result should be word-split.
+
+/// A word produced by [`tokenize`], tagged with the quoting context it was built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    /// The word's text, with surrounding quotes stripped and escapes resolved.
+    pub text: String,
+    /// `true` if the word was built entirely from single-quoted text. A literal word is never
+    /// `$VAR`/`$(...)` expanded; a non-literal word is expanded as a whole, including any
+    /// single-quoted part of it.
+    pub literal: bool,
+    /// `true` if any part of the word was quoted (single or double). A fully unquoted word has
+    /// its expansion result word-split on whitespace; a quoted one does not.
+    pub quoted: bool,
+}
+
+impl PartialEq<&str> for Word {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
+
+/// Which file descriptor a redirection targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fd {
+    Stdout,
+    Stderr,
+}
+
+/// Whether a redirection truncates or appends to its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirMode {
+    Truncate,
+    Append,
+}
+
+/// A single lexical token produced by [`tokenize`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    /// A command name, argument, or redirection target.
+    Word(Word),
+    /// `|`
+    Pipe,
+    /// `<`
+    RedirectIn,
+    /// `>`, `>>`, `2>` or `2>>`.
+    Redirect(Fd, RedirMode),
+    /// `&`
+    Background,
+    /// `;`
+    Semicolon,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+}
+
+/// State of the quote the lexer is currently inside, if any.
+#[derive(PartialEq)]
+enum Quote {
+    None,
+    Single,
+    Double,
+}
+
+/// Accumulates the text and quoting context of the word currently being built.
+#[derive(Default)]
+struct WordBuilder {
+    text: String,
+    in_progress: bool,
+    saw_single: bool,
+    saw_double: bool,
+    saw_bare: bool,
+}
+
+impl WordBuilder {
+    /// Appends a character that came from outside any quotes (including `$(...)`/backtick spans
+    /// and backslash escapes): disqualifies the word from being `literal`.
+    fn push_bare(&mut self, c: char) {
+        self.in_progress = true;
+        self.saw_bare = true;
+        self.text.push(c);
+    }
+
+    /// Appends a character from inside a quoted span, without affecting `literal`.
+    fn push_quoted(&mut self, c: char) {
+        self.in_progress = true;
+        self.text.push(c);
+    }
+
+    fn enter_single_quote(&mut self) {
+        self.in_progress = true;
+        self.saw_single = true;
+    }
+
+    fn enter_double_quote(&mut self) {
+        self.in_progress = true;
+        self.saw_double = true;
+    }
+
+    fn take(&mut self) -> Option<Word> {
+        if !self.in_progress {
+            return None;
+        }
+        let word = Word {
+            text: std::mem::take(&mut self.text),
+            literal: self.saw_single && !self.saw_double && !self.saw_bare,
+            quoted: self.saw_single || self.saw_double,
+        };
+        *self = WordBuilder::default();
+        Some(word)
+    }
+}
+
+/// Splits `input` into a stream of [`Token`]s, honoring single quotes, double quotes, backslash
+/// escapes, and `$(...)`/backtick command-substitution spans.
+///
+/// Single-quoted text is taken literally. Double-quoted text allows `\"`, `\\` and `\$` escapes
+/// (other backslashes are kept as-is). Outside of quotes, a backslash escapes the following
+/// character, including whitespace and the `|`, `<`, `>`, `&` and `;` operators. `|`, `<`, `>`,
+/// `&` and `;` are only recognized as operators when they appear unquoted; `&&` and `||` are
+/// recognized greedily over their single-character forms. A `$(` or `` ` `` that appears
+/// unquoted opens a substitution span that runs, parens/backticks balanced, up to its closing
+/// delimiter; whitespace inside it does not split the word.
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut word = WordBuilder::default();
+    let mut quote = Quote::None;
+
+    macro_rules! flush {
+        () => {
+            if let Some(word) = word.take() {
+                tokens.push(Token::Word(word));
+            }
+        };
+    }
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Quote::Single => {
+                if c == '\'' {
+                    quote = Quote::None;
+                } else {
+                    word.push_quoted(c);
+                }
+            }
+            Quote::Double => match c {
+                '"' => quote = Quote::None,
+                '\\' => match chars.peek() {
+                    Some('"') | Some('\\') | Some('$') => word.push_quoted(chars.next().unwrap()),
+                    _ => word.push_quoted(c),
+                },
+                c => word.push_quoted(c),
+            },
+            Quote::None => match c {
+                '\'' => {
+                    word.enter_single_quote();
+                    quote = Quote::Single;
+                }
+                '"' => {
+                    word.enter_double_quote();
+                    quote = Quote::Double;
+                }
+                '\\' => {
+                    if let Some(next) = chars.next() {
+                        word.push_bare(next);
+                    }
+                }
+                '$' if chars.peek() == Some(&'(') => {
+                    word.push_bare('$');
+                    word.push_bare(chars.next().unwrap());
+                    push_balanced(&mut word, &mut chars, '(', ')');
+                }
+                '`' => {
+                    word.push_bare('`');
+                    push_balanced(&mut word, &mut chars, '`', '`');
+                }
+                c if c.is_whitespace() => flush!(),
+                '|' => {
+                    flush!();
+                    if chars.peek() == Some(&'|') {
+                        chars.next();
+                        tokens.push(Token::Or);
+                    } else {
+                        tokens.push(Token::Pipe);
+                    }
+                }
+                ';' => {
+                    flush!();
+                    tokens.push(Token::Semicolon);
+                }
+                '<' => {
+                    flush!();
+                    tokens.push(Token::RedirectIn);
+                }
+                '2' if !word.in_progress && chars.peek() == Some(&'>') => {
+                    chars.next();
+                    tokens.push(Token::Redirect(Fd::Stderr, redirect_mode(&mut chars)));
+                }
+                '>' => {
+                    flush!();
+                    tokens.push(Token::Redirect(Fd::Stdout, redirect_mode(&mut chars)));
+                }
+                '&' => {
+                    flush!();
+                    if chars.peek() == Some(&'&') {
+                        chars.next();
+                        tokens.push(Token::And);
+                    } else {
+                        tokens.push(Token::Background);
+                    }
+                }
+                c => word.push_bare(c),
+            },
+        }
+    }
+
+    flush!();
+    tokens
+}
+
+/// Consumes a second `>` if present, turning a `>`/`2>` into its append form `>>`/`2>>`.
+fn redirect_mode(chars: &mut std::iter::Peekable<std::str::Chars>) -> RedirMode {
+    if chars.peek() == Some(&'>') {
+        chars.next();
+        RedirMode::Append
+    } else {
+        RedirMode::Truncate
+    }
+}
+
+/// Copies characters verbatim into `word` up to (and including) the `close` delimiter that
+/// balances the `open` already consumed. Used to keep `$(...)` and `` `...` `` spans whole
+/// (including their internal whitespace) while the `open == close` case (backticks) just reads
+/// up to the next one, since backticks don't nest.
+fn push_balanced(
+    word: &mut WordBuilder,
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    open: char,
+    close: char,
+) {
+    let mut depth = 1;
+    for c in chars.by_ref() {
+        word.push_bare(c);
+        if open != close && c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(tokens: &[Token]) -> Vec<&str> {
+        tokens
+            .iter()
+            .map(|token| match token {
+                Token::Word(word) => word.text.as_str(),
+                _ => panic!("expected a Word token, got {:?}", token),
+            })
+            .collect()
+    }
+
+    fn word(text: &str, literal: bool, quoted: bool) -> Token {
+        Token::Word(Word {
+            text: text.to_string(),
+            literal,
+            quoted,
+        })
+    }
+
+    #[test]
+    fn empty_input_has_no_tokens() {
+        assert_eq!(tokenize(""), vec![]);
+    }
+
+    #[test]
+    fn splits_on_unquoted_whitespace() {
+        let tokens = tokenize("wc -l file.txt");
+        assert_eq!(words(&tokens), vec!["wc", "-l", "file.txt"]);
+    }
+
+    #[test]
+    fn double_quoted_argument_keeps_its_spaces() {
+        let tokens = tokenize(r#"echo "hello world""#);
+        assert_eq!(words(&tokens), vec!["echo", "hello world"]);
+    }
+
+    #[test]
+    fn single_quoted_argument_is_literal() {
+        let tokens = tokenize("grep 'a | b' file.txt");
+        assert_eq!(words(&tokens), vec!["grep", "a | b", "file.txt"]);
+        assert_eq!(tokens[1], word("a | b", true, true));
+    }
+
+    #[test]
+    fn unquoted_word_is_neither_literal_nor_quoted() {
+        let tokens = tokenize("echo $HOME");
+        assert_eq!(tokens[1], word("$HOME", false, false));
+    }
+
+    #[test]
+    fn double_quoted_word_is_quoted_but_not_literal() {
+        let tokens = tokenize(r#"echo "$HOME""#);
+        assert_eq!(tokens[1], word("$HOME", false, true));
+    }
+
+    #[test]
+    fn redirect_inside_quotes_is_not_an_operator() {
+        let tokens = tokenize(r#"echo "a > b""#);
+        assert_eq!(words(&tokens), vec!["echo", "a > b"]);
+    }
+
+    #[test]
+    fn operators_are_recognized_when_unquoted() {
+        let tokens = tokenize("cat file.txt | wc -l < in.txt > out.txt &");
+        assert_eq!(
+            tokens,
+            vec![
+                word("cat", false, false),
+                word("file.txt", false, false),
+                Token::Pipe,
+                word("wc", false, false),
+                word("-l", false, false),
+                Token::RedirectIn,
+                word("in.txt", false, false),
+                Token::Redirect(Fd::Stdout, RedirMode::Truncate),
+                word("out.txt", false, false),
+                Token::Background,
+            ]
+        );
+    }
+
+    #[test]
+    fn backslash_escapes_a_double_quote() {
+        let tokens = tokenize(r#"echo \"hi\""#);
+        assert_eq!(words(&tokens), vec!["echo", "\"hi\""]);
+    }
+
+    #[test]
+    fn backslash_escapes_an_operator() {
+        let tokens = tokenize(r"echo a\|b");
+        assert_eq!(words(&tokens), vec!["echo", "a|b"]);
+    }
+
+    #[test]
+    fn adjacent_quoted_and_unquoted_segments_form_one_word() {
+        let tokens = tokenize(r#"echo foo"bar baz"qux"#);
+        assert_eq!(words(&tokens), vec!["echo", "foobar bazqux"]);
+    }
+
+    #[test]
+    fn empty_quotes_produce_an_empty_word() {
+        let tokens = tokenize(r#"echo """#);
+        assert_eq!(words(&tokens), vec!["echo", ""]);
+    }
+
+    #[test]
+    fn dollar_paren_substitution_keeps_internal_whitespace() {
+        let tokens = tokenize("echo $(echo a b)");
+        assert_eq!(words(&tokens), vec!["echo", "$(echo a b)"]);
+    }
+
+    #[test]
+    fn nested_dollar_paren_substitution_is_balanced() {
+        let tokens = tokenize("echo $(echo $(echo a))");
+        assert_eq!(words(&tokens), vec!["echo", "$(echo $(echo a))"]);
+    }
+
+    #[test]
+    fn backtick_substitution_keeps_internal_whitespace() {
+        let tokens = tokenize("echo `echo a b`");
+        assert_eq!(words(&tokens), vec!["echo", "`echo a b`"]);
+    }
+
+    #[test]
+    fn double_angle_bracket_appends_to_stdout() {
+        let tokens = tokenize("cmd >> log.txt");
+        assert_eq!(tokens[1], Token::Redirect(Fd::Stdout, RedirMode::Append));
+    }
+
+    #[test]
+    fn two_angle_bracket_truncates_stderr() {
+        let tokens = tokenize("cmd 2> errors.txt");
+        assert_eq!(tokens[1], Token::Redirect(Fd::Stderr, RedirMode::Truncate));
+    }
+
+    #[test]
+    fn two_double_angle_bracket_appends_to_stderr() {
+        let tokens = tokenize("cmd 2>> errors.txt");
+        assert_eq!(tokens[1], Token::Redirect(Fd::Stderr, RedirMode::Append));
+    }
+
+    #[test]
+    fn a_bare_two_is_still_a_word() {
+        let tokens = tokenize("echo 2 file2.txt");
+        assert_eq!(words(&tokens), vec!["echo", "2", "file2.txt"]);
+    }
+
+    #[test]
+    fn semicolon_separates_commands() {
+        let tokens = tokenize("echo hi; echo bye");
+        assert_eq!(
+            tokens,
+            vec![
+                word("echo", false, false),
+                word("hi", false, false),
+                Token::Semicolon,
+                word("echo", false, false),
+                word("bye", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_ampersand_is_an_and_operator_not_two_backgrounds() {
+        let tokens = tokenize("echo hi && echo bye");
+        assert_eq!(
+            tokens,
+            vec![
+                word("echo", false, false),
+                word("hi", false, false),
+                Token::And,
+                word("echo", false, false),
+                word("bye", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn double_pipe_is_an_or_operator_not_a_pipe() {
+        let tokens = tokenize("echo hi || echo bye");
+        assert_eq!(
+            tokens,
+            vec![
+                word("echo", false, false),
+                word("hi", false, false),
+                Token::Or,
+                word("echo", false, false),
+                word("bye", false, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn single_ampersand_is_still_background() {
+        let tokens = tokenize("echo hi &");
+        assert_eq!(
+            tokens,
+            vec![word("echo", false, false), word("hi", false, false), Token::Background]
+        );
+    }
+}